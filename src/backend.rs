@@ -0,0 +1,177 @@
+use log::{debug, error, info, warn};
+use std::future::Future;
+use tokio::{
+    select,
+    sync::mpsc,
+    task,
+    time::{sleep, Duration},
+};
+
+use crate::{
+    config::Config, errors::ServiceError, models::SpeedTestResult, stats, stats::StatsAggregate,
+    tests::perform_all_tests, TestResults, CONFIG,
+};
+
+/// What a single poll of the MQTT connection turned up, abstracted away from
+/// the concrete v3/v5 packet types so [`run`] only has to reason about
+/// outcomes it actually reacts to.
+pub enum PollOutcome<Request> {
+    /// Connection (re)established — time to republish `online`.
+    Connected,
+    /// A command came in on the command topic, optionally carrying a
+    /// request to correlate the reply with (MQTT v5 only).
+    Command(Option<Request>),
+    /// Anything else, logged at debug level and otherwise ignored.
+    Other,
+}
+
+/// An MQTT wire protocol (v3.1.1 or v5) the bridge can speak. Implementing
+/// this once per protocol lets [`run`] drive the connect/discover/publish/poll
+/// loop a single time instead of duplicating it per protocol, the way
+/// `run_v3`/`run_v5` used to.
+pub trait MqttBackend {
+    type Client: Clone + Send + 'static;
+    type Connection: Send + 'static;
+    /// Correlation context for an on-demand request, if the protocol
+    /// supports one (MQTT v5's `RequestContext`); `()` for v3.
+    type Request: Send + 'static;
+
+    // `impl Future<...> + Send` rather than plain `async fn`: RPITIT futures
+    // aren't `Send` by default, and these are driven inside `tokio::spawn`'d
+    // tasks in `run` below, which requires it.
+    fn initialize(
+        config: &Config,
+    ) -> impl Future<Output = Result<(Self::Client, Self::Connection), ServiceError>> + Send;
+    fn subscribe_command(client: &Self::Client, config: &Config) -> Result<(), ServiceError>;
+    fn publish_discovery(
+        client: &Self::Client,
+        config: &Config,
+    ) -> impl Future<Output = Result<(), ServiceError>> + Send;
+    fn publish_online(client: &Self::Client, config: &Config) -> Result<(), ServiceError>;
+    fn publish_results(client: &Self::Client, config: &Config, result: &SpeedTestResult);
+    fn publish_stats(
+        client: &Self::Client,
+        config: &Config,
+        aggregate: &StatsAggregate,
+    ) -> Result<(), ServiceError>;
+    fn publish_response(
+        client: &Self::Client,
+        request: &Self::Request,
+        payload: String,
+    ) -> Result<(), ServiceError>;
+    fn poll(
+        connection: &mut Self::Connection,
+        config: &Config,
+    ) -> impl Future<Output = Result<PollOutcome<Self::Request>, ServiceError>> + Send;
+}
+
+/// Drives the connect/discover/publish/poll loop for a given [`MqttBackend`].
+/// This is the protocol-agnostic body `run_v3`/`run_v5` used to duplicate.
+pub async fn run<B: MqttBackend>() -> Result<(), ServiceError> {
+    // Channel for sending test results (plus the request they answer, if
+    // any) to the MQTT publishing task
+    let (result_tx, mut result_rx) = mpsc::channel::<(TestResults, Option<B::Request>)>(1);
+    // Channel the speed-test loop selects on to run out of band when a
+    // command is received on the MQTT command topic. Unbounded because
+    // `perform_all_tests` can run for many seconds; a bounded channel would
+    // drop (and silently lose the correlation for) any command that arrives
+    // while one is already in flight. Queued requests are drained one per
+    // loop iteration, each getting its own correlated reply.
+    let (trigger_tx, mut trigger_rx) = mpsc::unbounded_channel::<Option<B::Request>>();
+
+    let (mqtt_client, mut mqtt_connection) = B::initialize(&CONFIG).await?;
+    let eventloop_client = mqtt_client.clone();
+
+    let speed_test_task = task::spawn(async move {
+        loop {
+            let request = select! {
+                _ = sleep(Duration::from_secs(CONFIG.check_interval)) => None,
+                Some(request) = trigger_rx.recv() => {
+                    info!("On-demand speed test triggered via MQTT command topic");
+                    request
+                }
+            };
+
+            match perform_all_tests(&CONFIG).await {
+                Ok(results) => {
+                    if let Err(err) = result_tx.send((results, request)).await {
+                        warn!("Failed to send test results to MQTT: {:?}", err);
+                    }
+                }
+                Err(err) => error!("Speedtest failed: {:?}", err),
+            }
+        }
+    });
+
+    // Task to manage MQTT publishing and connection
+    let mqtt_publish_task = tokio::spawn(async move {
+        // Publish discovery message for Home Assistant auto-discovery
+        if let Err(err) = B::publish_discovery(&mqtt_client, &CONFIG).await {
+            error!("MQTT disovery message publish error: {:?}", err);
+        }
+
+        let mut stats = stats::StatsWindow::new(Duration::from_secs(CONFIG.stats_window));
+
+        while let Some((results, request)) = result_rx.recv().await {
+            stats.push(results);
+
+            let speed_test_result =
+                SpeedTestResult::new(results.download, results.upload, results.ping);
+            B::publish_results(&mqtt_client, &CONFIG, &speed_test_result);
+
+            if let Some(aggregate) = stats.aggregate() {
+                if let Err(err) = B::publish_stats(&mqtt_client, &CONFIG, &aggregate) {
+                    error!("MQTT publish error: {:?}", err);
+                }
+            }
+
+            if let Some(request) = request {
+                let payload = serde_json::json!({
+                    "download": results.download,
+                    "upload": results.upload,
+                    "ping": results.ping,
+                })
+                .to_string();
+
+                if let Err(err) = B::publish_response(&mqtt_client, &request, payload) {
+                    error!("Failed to publish correlated response: {:?}", err);
+                }
+            }
+        }
+    });
+
+    // Task to handle MQTT connection events
+    let mqtt_eventloop_task = tokio::spawn(async move {
+        loop {
+            match B::poll(&mut mqtt_connection, &CONFIG).await {
+                Ok(PollOutcome::Connected) => {
+                    debug!("MQTT connection (re)established");
+                    if let Err(err) = B::publish_online(&eventloop_client, &CONFIG) {
+                        error!("Failed to publish MQTT availability: {:?}", err);
+                    }
+                    // `clean_session(true)` means the broker drops our
+                    // subscriptions on every disconnect, so re-issue this
+                    // alongside `publish_online` on every (re)connect, not
+                    // just once before the loop starts.
+                    if let Err(err) = B::subscribe_command(&eventloop_client, &CONFIG) {
+                        error!("Failed to subscribe to MQTT command topic: {:?}", err);
+                    }
+                }
+                Ok(PollOutcome::Command(request)) => {
+                    debug!("Received on-demand test command");
+                    if let Err(err) = trigger_tx.send(request) {
+                        warn!("Failed to queue on-demand test trigger: {:?}", err);
+                    }
+                }
+                Ok(PollOutcome::Other) => {}
+                Err(err) => {
+                    error!("MQTT connection error: {:?}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    });
+
+    let _ = tokio::join!(speed_test_task, mqtt_publish_task, mqtt_eventloop_task);
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+use serde_json::{json, Value};
+
+use crate::{config::Config, errors::ServiceError};
+
+pub const PAYLOAD_ONLINE: &str = "online";
+pub const PAYLOAD_OFFLINE: &str = "offline";
+
+/// One Home Assistant MQTT Discovery sensor: a topic key plus the bits of
+/// the discovery payload that differ per sensor.
+pub struct SensorSpec {
+    pub key: &'static str,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub device_class: &'static str,
+}
+
+/// The three instantaneous readings published on every cycle.
+pub const INSTANT_SENSORS: &[SensorSpec] = &[
+    SensorSpec {
+        key: "download",
+        name: "Speedtest download",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "upload",
+        name: "Speedtest upload",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "ping",
+        name: "Speedtest ping",
+        unit: "ms",
+        device_class: "duration",
+    },
+];
+
+/// Rolling min/max/mean/jitter sensors, all sourced from the aggregate JSON
+/// published to the attributes topic alongside each cycle's readings.
+pub const STATS_SENSORS: &[SensorSpec] = &[
+    SensorSpec {
+        key: "download_min",
+        name: "Speedtest download (min)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "download_max",
+        name: "Speedtest download (max)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "download_avg",
+        name: "Speedtest download (avg)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "upload_min",
+        name: "Speedtest upload (min)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "upload_max",
+        name: "Speedtest upload (max)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "upload_avg",
+        name: "Speedtest upload (avg)",
+        unit: "Mbit/s",
+        device_class: "data_rate",
+    },
+    SensorSpec {
+        key: "ping_min",
+        name: "Speedtest ping (min)",
+        unit: "ms",
+        device_class: "duration",
+    },
+    SensorSpec {
+        key: "ping_max",
+        name: "Speedtest ping (max)",
+        unit: "ms",
+        device_class: "duration",
+    },
+    SensorSpec {
+        key: "ping_avg",
+        name: "Speedtest ping (avg)",
+        unit: "ms",
+        device_class: "duration",
+    },
+    SensorSpec {
+        key: "ping_jitter",
+        name: "Speedtest ping jitter",
+        unit: "ms",
+        device_class: "duration",
+    },
+];
+
+/// Retained discovery config topic for `spec`, e.g. `<prefix>/download/config`.
+pub fn config_topic(config: &Config, spec: &SensorSpec) -> String {
+    format!("{}/{}/config", config.mqtt_topic_prefix, spec.key)
+}
+
+/// Identifier for this bridge's HA device/entities, derived from
+/// `mqtt_topic_prefix` rather than hardcoded, so two bridges publishing
+/// under different prefixes (e.g. one per location) get distinct
+/// `unique_id`s instead of the second bridge's discovery silently taking
+/// over the first one's entity rows in HA's registry.
+fn device_id(config: &Config) -> String {
+    let sanitized: String = config
+        .mqtt_topic_prefix
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("speedtest_{}", sanitized)
+}
+
+/// Discovery payload for an instant sensor, pulling `spec.key` out of the
+/// shared state topic (the `{status, download, upload, ping}` payload
+/// `publish_results` sends every cycle) via `value_template`.
+pub fn instant_sensor_config(
+    config: &Config,
+    availability_topic: &str,
+    state_topic: &str,
+    spec: &SensorSpec,
+) -> Value {
+    let device_id = device_id(config);
+    json!({
+        "name": spec.name,
+        "state_topic": state_topic,
+        "value_template": format!("{{{{ value_json.{} }}}}", spec.key),
+        "unit_of_measurement": spec.unit,
+        "device_class": spec.device_class,
+        "unique_id": format!("{}_{}", device_id, spec.key),
+        "availability_topic": availability_topic,
+        "payload_available": PAYLOAD_ONLINE,
+        "payload_not_available": PAYLOAD_OFFLINE,
+        "device": {
+            "name": "Speedtest",
+            "identifiers": [device_id]
+        }
+    })
+}
+
+/// Discovery payload for a stats sensor, pulling `spec.key` out of the
+/// shared attributes topic via `value_template`.
+pub fn stats_sensor_config(
+    config: &Config,
+    availability_topic: &str,
+    stats_topic: &str,
+    spec: &SensorSpec,
+) -> Value {
+    let device_id = device_id(config);
+    json!({
+        "name": spec.name,
+        "state_topic": stats_topic,
+        "json_attributes_topic": stats_topic,
+        "value_template": format!("{{{{ value_json.{} }}}}", spec.key),
+        "unit_of_measurement": spec.unit,
+        "device_class": spec.device_class,
+        "unique_id": format!("{}_{}", device_id, spec.key),
+        "availability_topic": availability_topic,
+        "payload_available": PAYLOAD_ONLINE,
+        "payload_not_available": PAYLOAD_OFFLINE,
+        "device": {
+            "name": "Speedtest",
+            "identifiers": [device_id]
+        }
+    })
+}
+
+/// Retries a single retained publish up to 3 times. `attempt` performs one
+/// publish and maps its client-specific error into a `ServiceError`, so this
+/// retry loop is shared by both the v3 and v5 discovery flows even though
+/// their underlying client/error types differ.
+pub async fn publish_retained(
+    mut attempt: impl FnMut() -> Result<(), ServiceError>,
+    label: &str,
+) -> Result<(), ServiceError> {
+    for try_n in 1..=3 {
+        match attempt() {
+            Ok(()) => {
+                info!("Published MQTT discovery message for '{}'", label);
+                return Ok(());
+            }
+            Err(err) if try_n < 3 => {
+                warn!(
+                    "Retrying MQTT publish for '{}' (attempt {}/3): {:?}",
+                    label, try_n, err
+                );
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to publish MQTT discovery message for '{}': {:?}",
+                    label, err
+                );
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
@@ -8,6 +8,20 @@ pub enum ServiceError {
     TaskJoinError,
     #[error("SpeedTest error: {0:?}")]
     SpeedTest(SpeedTestError),
+    #[error("MQTT client error: {0:?}")]
+    MqttClientError(rumqttc::ClientError),
+    #[error("MQTT v5 client error: {0:?}")]
+    MqttV5ClientError(rumqttc::v5::ClientError),
+    #[error("MQTT connection error: {0:?}")]
+    MqttConnectionError(rumqttc::ConnectionError),
+    #[error("MQTT v5 connection error: {0:?}")]
+    MqttV5ConnectionError(rumqttc::v5::ConnectionError),
+    #[error("Failed to read TLS certificate/key file: {0}")]
+    TlsConfig(std::io::Error),
+    #[error("Failed to parse TLS certificate/key")]
+    TlsCertParse,
+    #[error("Unknown speedtest server id: {0}")]
+    UnknownSpeedtestServer(u32),
 }
 
 // Implement conversion from `JoinError` to `ServiceError`
@@ -23,3 +37,10 @@ impl From<SpeedTestError> for ServiceError {
         ServiceError::SpeedTest(error)
     }
 }
+
+// Implement conversion from `std::io::Error` to `ServiceError`
+impl From<std::io::Error> for ServiceError {
+    fn from(error: std::io::Error) -> Self {
+        ServiceError::TlsConfig(error)
+    }
+}
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::Serialize;
 use serde_json::json;
 
+use crate::{config::Config, mqtt_shared};
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SpeedTestResult {
     pub download: f64,
@@ -19,6 +21,27 @@ impl SpeedTestResult {
             timestamp: Utc::now(),
         }
     }
+
+    /// Builds the MQTT messages for this result, rooted under `config`'s
+    /// topic prefix via the same [`mqtt_shared::state_topic`]/
+    /// [`mqtt_shared::attributes_topic`] helpers discovery uses, so state
+    /// and discovery topics can't silently drift apart.
+    pub fn to_mqtt_messages(&self, config: &Config) -> Vec<MqttMessage> {
+        vec![MqttMessage {
+            name: "Speedtest Results".to_string(),
+            state_topic: mqtt_shared::state_topic(config),
+            json_attributes_topic: Some(mqtt_shared::attributes_topic(config)),
+            unit_of_measurement: None,
+            value_template: Some("{{ value_json.status }}".to_string()),
+            payload: json!({
+                "status": "ok",
+                "download": self.download,
+                "upload": self.upload,
+                "ping": self.ping
+            })
+            .to_string(),
+        }]
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -30,22 +53,3 @@ pub struct MqttMessage {
     pub value_template: Option<String>,
     pub payload: String,
 }
-
-impl From<SpeedTestResult> for MqttMessage {
-    fn from(result: SpeedTestResult) -> Self {
-        MqttMessage {
-            name: "Speedtest Results".to_string(),
-            state_topic: "homeassistant/sensor/speedtest/state".to_string(),
-            json_attributes_topic: Some("homeassistant/sensor/speedtest/attributes".to_string()),
-            unit_of_measurement: None,
-            value_template: Some("{{ value_json.status }}".to_string()),
-            payload: json!({
-                "status": "ok",
-                "download": result.download,
-                "upload": result.upload,
-                "ping": result.ping
-            })
-            .to_string(),
-        }
-    }
-}
@@ -1,69 +1,139 @@
-use crate::{config::Config, errors::ServiceError};
-use log::{error, info, warn};
-use rumqttc::{Client, MqttOptions, QoS};
-use serde_json::json;
+use crate::{
+    backend::{MqttBackend, PollOutcome},
+    config::Config,
+    discovery::PAYLOAD_OFFLINE,
+    errors::ServiceError,
+    models::SpeedTestResult,
+    mqtt_shared::{self, MqttClientBuilder, MqttOptionsExt, MqttPublish},
+    stats::StatsAggregate,
+};
+use log::{debug, error};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS, Transport};
 use std::time::Duration;
 
-pub async fn initialize_mqtt(
-    config: &Config,
-) -> Result<(Client, rumqttc::Connection), rumqttc::ClientError> {
-    let mut mqtt_options = MqttOptions::new(&config.mqtt_id, &config.mqtt_host, config.mqtt_port);
-    mqtt_options.set_keep_alive(Duration::from_secs(5));
-    mqtt_options.set_clean_session(true);
-    if let (Some(username), Some(password)) = (&config.mqtt_username, &config.mqtt_password) {
-        mqtt_options.set_credentials(username, password);
+impl MqttOptionsExt for MqttOptions {
+    fn new(id: &str, host: &str, port: u16) -> Self {
+        MqttOptions::new(id, host, port)
+    }
+
+    fn set_keep_alive(&mut self, duration: Duration) -> &mut Self {
+        self.set_keep_alive(duration)
+    }
+
+    fn set_clean_session(&mut self, clean_session: bool) -> &mut Self {
+        self.set_clean_session(clean_session)
+    }
+
+    fn set_credentials(&mut self, username: &str, password: &str) -> &mut Self {
+        self.set_credentials(username, password)
+    }
+
+    fn set_transport(&mut self, transport: Transport) -> &mut Self {
+        self.set_transport(transport)
+    }
+
+    fn set_offline_last_will(&mut self, topic: String) -> &mut Self {
+        self.set_last_will(LastWill::new(
+            topic,
+            PAYLOAD_OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ))
     }
-    Ok(Client::new(mqtt_options, 10))
 }
 
-pub async fn publish_discovery_message(client: &Client) -> Result<(), ServiceError> {
-    let discovery_messages = vec![
-        ("download", "Mbit/s", "data_rate"),
-        ("upload", "Mbit/s", "data_rate"),
-        ("ping", "ms", "duration"),
-    ];
-
-    for (name, unit, device_class) in discovery_messages {
-        let config_topic = format!("homeassistant/sensor/speedtest/{}/config", name);
-        let config_message = json!({
-            "name": format!("Speedtest {}", name),
-            "state_topic": format!("homeassistant/sensor/speedtest/{}", name),
-            "unit_of_measurement": unit,
-            "device_class": device_class,
-            "unique_id": format!("speedtest_{}", name),
-            "device": {
-                "name": "Speedtest",
-                "identifiers": ["speedtest_device"]
-            }
-        });
-
-        for attempt in 1..=3 {
-            match client.publish(
-                config_topic.clone(),
-                QoS::AtLeastOnce,
-                true,
-                config_message.to_string(),
-            ) {
-                Ok(_) => {
-                    info!("Published MQTT discovery message for '{}'", name);
-                    break;
-                }
-                Err(err) if attempt < 3 => {
-                    warn!(
-                        "Retrying MQTT publish for '{}' (attempt {}/3): {:?}",
-                        name, attempt, err
-                    );
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-                Err(err) => {
-                    error!(
-                        "Failed to publish MQTT discovery message for '{}': {:?}",
-                        name, err
-                    );
-                    return Err(ServiceError::MqttClientError(err));
-                }
-            }
+impl MqttClientBuilder<MqttOptions> for Client {
+    type Connection = Connection;
+
+    fn build(options: MqttOptions, cap: usize) -> (Self, Self::Connection) {
+        Client::new(options, cap)
+    }
+}
+
+impl MqttPublish for Client {
+    fn publish_msg(&self, topic: &str, retain: bool, payload: String) -> Result<(), ServiceError> {
+        self.publish(topic, QoS::AtLeastOnce, retain, payload)
+            .map_err(ServiceError::MqttClientError)
+    }
+
+    fn subscribe_topic(&self, topic: &str) -> Result<(), ServiceError> {
+        self.subscribe(topic, QoS::AtLeastOnce)
+            .map_err(ServiceError::MqttClientError)
+    }
+}
+
+/// Returns `Err` on a connection error rather than exiting itself; `run`
+/// is the only place that decides whether a disconnect is fatal.
+async fn poll(connection: &mut Connection, config: &Config) -> Result<PollOutcome<()>, ServiceError> {
+    match connection.eventloop.poll().await {
+        Ok(Event::Incoming(Packet::ConnAck(_))) => Ok(PollOutcome::Connected),
+        Ok(Event::Incoming(Packet::Publish(publish)))
+            if publish.topic == mqtt_shared::command_topic(config) =>
+        {
+            Ok(PollOutcome::Command(None))
+        }
+        Ok(notification) => {
+            debug!("Received MQTT event: {:?}", notification);
+            Ok(PollOutcome::Other)
         }
+        Err(err) => {
+            error!("MQTT connection error: {:?}", err);
+            Err(ServiceError::MqttConnectionError(err))
+        }
+    }
+}
+
+/// Plain MQTT v3.1.1 flow: fixed-interval measurements plus "run now" commands
+/// with no correlated reply.
+pub struct V3Backend;
+
+impl MqttBackend for V3Backend {
+    type Client = Client;
+    type Connection = Connection;
+    type Request = ();
+
+    async fn initialize(config: &Config) -> Result<(Self::Client, Self::Connection), ServiceError> {
+        mqtt_shared::initialize_mqtt::<MqttOptions, Client>(config).await
+    }
+
+    fn subscribe_command(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::subscribe_command_topic(client, config)
+    }
+
+    async fn publish_discovery(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::publish_discovery_message(client, config).await
+    }
+
+    fn publish_online(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::publish_online(client, config)
+    }
+
+    fn publish_results(client: &Self::Client, config: &Config, result: &SpeedTestResult) {
+        mqtt_shared::publish_results(client, config, result)
+    }
+
+    fn publish_stats(
+        client: &Self::Client,
+        config: &Config,
+        aggregate: &StatsAggregate,
+    ) -> Result<(), ServiceError> {
+        mqtt_shared::publish_stats(client, config, aggregate)
+    }
+
+    fn publish_response(
+        _client: &Self::Client,
+        _request: &Self::Request,
+        _payload: String,
+    ) -> Result<(), ServiceError> {
+        // MQTT v3 has no correlation properties, so on-demand commands never
+        // carry a request to reply to.
+        Ok(())
+    }
+
+    async fn poll(
+        connection: &mut Self::Connection,
+        config: &Config,
+    ) -> Result<PollOutcome<Self::Request>, ServiceError> {
+        poll(connection, config).await
     }
-    Ok(())
 }
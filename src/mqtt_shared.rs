@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use log::{error, info};
+use rumqttc::Transport;
+
+use crate::{
+    config::Config,
+    discovery::{self, PAYLOAD_ONLINE},
+    errors::ServiceError,
+    models::SpeedTestResult,
+    stats::StatsAggregate,
+    tls,
+};
+
+/// Retained topic Home Assistant watches to tell whether the bridge is alive.
+pub fn availability_topic(config: &Config) -> String {
+    format!("{}/status", config.mqtt_topic_prefix)
+}
+
+/// Topic a client publishes to in order to force an out-of-band speed test.
+pub fn command_topic(config: &Config) -> String {
+    format!("{}/command", config.mqtt_topic_prefix)
+}
+
+/// Topic the rolling stats aggregate (min/max/mean/jitter) is published to.
+pub fn attributes_topic(config: &Config) -> String {
+    format!("{}/attributes", config.mqtt_topic_prefix)
+}
+
+/// Topic `publish_results` sends the `{status, download, upload, ping}`
+/// payload to every cycle; the instant sensors read their value back out of
+/// it via `value_template`.
+pub fn state_topic(config: &Config) -> String {
+    format!("{}/state", config.mqtt_topic_prefix)
+}
+
+/// The bits of `rumqttc::MqttOptions`/`rumqttc::v5::MqttOptions` that
+/// [`initialize_mqtt`] needs, so the option-building sequence can be written
+/// once instead of copied per protocol. The `LastWill` payload shape is the
+/// one place the two option builders actually diverge (v5 adds a trailing
+/// properties argument), which is why it's hidden behind
+/// `set_offline_last_will` rather than exposed directly.
+pub trait MqttOptionsExt: Sized {
+    fn new(id: &str, host: &str, port: u16) -> Self;
+    fn set_keep_alive(&mut self, duration: Duration) -> &mut Self;
+    fn set_clean_session(&mut self, clean_session: bool) -> &mut Self;
+    fn set_credentials(&mut self, username: &str, password: &str) -> &mut Self;
+    fn set_transport(&mut self, transport: Transport) -> &mut Self;
+    fn set_offline_last_will(&mut self, topic: String) -> &mut Self;
+}
+
+/// Builds the `(Client, Connection)` pair from already-populated options;
+/// the one step in client setup tied to a concrete client type rather than
+/// to `MqttOptions`.
+pub trait MqttClientBuilder<Options>: Sized {
+    type Connection;
+    fn build(options: Options, cap: usize) -> (Self, Self::Connection);
+}
+
+/// Sets up keep-alive, clean session, the offline `LastWill`, optional
+/// credentials and optional TLS transport identically for both MQTT
+/// protocol versions. `O`/`C` supply the handful of bits (`LastWill` shape,
+/// concrete client type) the two actually differ on.
+pub async fn initialize_mqtt<O, C>(config: &Config) -> Result<(C, C::Connection), ServiceError>
+where
+    O: MqttOptionsExt,
+    C: MqttClientBuilder<O>,
+{
+    let mut options = O::new(&config.mqtt_id, &config.mqtt_host, config.mqtt_port);
+    options
+        .set_keep_alive(Duration::from_secs(5))
+        .set_clean_session(true)
+        .set_offline_last_will(availability_topic(config));
+    if let (Some(username), Some(password)) = (&config.mqtt_username, &config.mqtt_password) {
+        options.set_credentials(username, password);
+    }
+    // Plaintext TCP remains the default; MQTT_TLS=true switches to rustls,
+    // optionally with mutual TLS when a client cert/key pair is configured.
+    if config.mqtt_tls {
+        options.set_transport(tls::build_transport(config)?);
+    }
+    Ok(C::build(options, 10))
+}
+
+/// What both protocols' clients can do: publish a message (optionally
+/// retained) and subscribe to a topic, each mapping failures to its own
+/// `ServiceError::Mqtt*`/`MqttV5*` variant.
+pub trait MqttPublish {
+    fn publish_msg(&self, topic: &str, retain: bool, payload: String) -> Result<(), ServiceError>;
+    fn subscribe_topic(&self, topic: &str) -> Result<(), ServiceError>;
+}
+
+/// Flips the retained availability topic back to `online`. Call this once the
+/// connection is confirmed (on `Incoming::ConnAck`) so Home Assistant clears
+/// the "unavailable" state left over from a previous crash or clean exit.
+pub fn publish_online<C: MqttPublish>(client: &C, config: &Config) -> Result<(), ServiceError> {
+    client.publish_msg(&availability_topic(config), true, PAYLOAD_ONLINE.to_string())
+}
+
+/// Subscribes to [`command_topic`] so a Home Assistant button/automation can
+/// force an immediate speed test instead of waiting for `CHECK_INTERVAL`.
+pub fn subscribe_command_topic<C: MqttPublish>(
+    client: &C,
+    config: &Config,
+) -> Result<(), ServiceError> {
+    client.subscribe_topic(&command_topic(config))
+}
+
+pub async fn publish_discovery_message<C: MqttPublish>(
+    client: &C,
+    config: &Config,
+) -> Result<(), ServiceError> {
+    let availability = availability_topic(config);
+    let state = state_topic(config);
+
+    for spec in discovery::INSTANT_SENSORS {
+        let topic = discovery::config_topic(config, spec);
+        let payload =
+            discovery::instant_sensor_config(config, &availability, &state, spec).to_string();
+        discovery::publish_retained(|| client.publish_msg(&topic, true, payload.clone()), spec.key)
+            .await?;
+    }
+
+    let stats_topic = attributes_topic(config);
+    for spec in discovery::STATS_SENSORS {
+        let topic = discovery::config_topic(config, spec);
+        let payload =
+            discovery::stats_sensor_config(config, &availability, &stats_topic, spec).to_string();
+        discovery::publish_retained(|| client.publish_msg(&topic, true, payload.clone()), spec.key)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Publishes the latest reading to its state topic, logging (but not
+/// propagating) any publish failure so one bad message doesn't stop the rest.
+pub fn publish_results<C: MqttPublish>(client: &C, config: &Config, result: &SpeedTestResult) {
+    for message in result.to_mqtt_messages(config) {
+        match client.publish_msg(&message.state_topic, false, message.payload.clone()) {
+            Ok(_) => info!(
+                "Published Speedtest result to MQTT topic '{}': {}",
+                message.state_topic, message.payload
+            ),
+            Err(err) => error!("MQTT publish error: {:?}", err),
+        }
+    }
+}
+
+pub fn publish_stats<C: MqttPublish>(
+    client: &C,
+    config: &Config,
+    aggregate: &StatsAggregate,
+) -> Result<(), ServiceError> {
+    let payload = serde_json::to_string(aggregate).unwrap_or_default();
+    let topic = attributes_topic(config);
+    client.publish_msg(&topic, false, payload)?;
+    info!("Published Speedtest stats aggregate to '{}'", topic);
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use crate::{
+    backend::{MqttBackend, PollOutcome},
+    config::Config,
+    discovery::PAYLOAD_OFFLINE,
+    errors::ServiceError,
+    models::SpeedTestResult,
+    mqtt_shared::{self, MqttClientBuilder, MqttOptionsExt, MqttPublish},
+    stats::StatsAggregate,
+};
+use log::{debug, error};
+use rumqttc::v5::mqttbytes::v5::{LastWill, Packet, Publish, PublishProperties};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Client, Connection, Event, MqttOptions};
+use rumqttc::Transport;
+use std::time::Duration;
+
+impl MqttOptionsExt for MqttOptions {
+    fn new(id: &str, host: &str, port: u16) -> Self {
+        MqttOptions::new(id, host, port)
+    }
+
+    fn set_keep_alive(&mut self, duration: Duration) -> &mut Self {
+        self.set_keep_alive(duration)
+    }
+
+    fn set_clean_session(&mut self, clean_session: bool) -> &mut Self {
+        self.set_clean_session(clean_session)
+    }
+
+    fn set_credentials(&mut self, username: &str, password: &str) -> &mut Self {
+        self.set_credentials(username, password)
+    }
+
+    fn set_transport(&mut self, transport: Transport) -> &mut Self {
+        self.set_transport(transport)
+    }
+
+    fn set_offline_last_will(&mut self, topic: String) -> &mut Self {
+        self.set_last_will(LastWill::new(
+            topic,
+            PAYLOAD_OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+            None,
+        ))
+    }
+}
+
+impl MqttClientBuilder<MqttOptions> for Client {
+    type Connection = Connection;
+
+    fn build(options: MqttOptions, cap: usize) -> (Self, Self::Connection) {
+        Client::new(options, cap)
+    }
+}
+
+impl MqttPublish for Client {
+    fn publish_msg(&self, topic: &str, retain: bool, payload: String) -> Result<(), ServiceError> {
+        self.publish(topic, QoS::AtLeastOnce, retain, payload)
+            .map_err(ServiceError::MqttV5ClientError)
+    }
+
+    fn subscribe_topic(&self, topic: &str) -> Result<(), ServiceError> {
+        self.subscribe(topic, QoS::AtLeastOnce)
+            .map_err(ServiceError::MqttV5ClientError)
+    }
+}
+
+/// `ResponseTopic`/`CorrelationData` pulled off an incoming command publish,
+/// kept around so the matching result can be routed back once the test
+/// finishes and a caller juggling several in-flight requests can tell them
+/// apart.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub response_topic: String,
+    pub correlation_data: Vec<u8>,
+}
+
+/// Extracts the request/response properties from a command publish, if the
+/// caller set them. Plain "fire and forget" commands (no properties) still
+/// work as in the v3 flow, just without a correlated reply.
+pub fn request_context_from_publish(publish: &Publish) -> Option<RequestContext> {
+    let properties: &PublishProperties = publish.properties.as_ref()?;
+    let response_topic = properties.response_topic.clone()?;
+    let correlation_data = properties.correlation_data.as_ref()?.to_vec();
+    Some(RequestContext {
+        response_topic,
+        correlation_data,
+    })
+}
+
+/// Publishes `payload` to the requester's response topic, echoing back the
+/// same correlation data it sent in so it can match the reply to its request.
+pub fn publish_response(
+    client: &Client,
+    ctx: &RequestContext,
+    payload: String,
+) -> Result<(), ServiceError> {
+    let properties = PublishProperties {
+        correlation_data: Some(ctx.correlation_data.clone().into()),
+        ..Default::default()
+    };
+    client
+        .publish_with_properties(&ctx.response_topic, QoS::AtLeastOnce, false, payload, properties)
+        .map_err(ServiceError::MqttV5ClientError)
+}
+
+/// Returns `Err` on a connection error rather than exiting itself; `run`
+/// is the only place that decides whether a disconnect is fatal.
+async fn poll(connection: &mut Connection, config: &Config) -> Result<PollOutcome<RequestContext>, ServiceError> {
+    match connection.eventloop.poll().await {
+        Ok(Event::Incoming(Packet::ConnAck(_))) => Ok(PollOutcome::Connected),
+        Ok(Event::Incoming(Packet::Publish(publish)))
+            if publish.topic == mqtt_shared::command_topic(config) =>
+        {
+            Ok(PollOutcome::Command(request_context_from_publish(&publish)))
+        }
+        Ok(notification) => {
+            debug!("Received MQTT event: {:?}", notification);
+            Ok(PollOutcome::Other)
+        }
+        Err(err) => {
+            error!("MQTT connection error: {:?}", err);
+            Err(ServiceError::MqttV5ConnectionError(err))
+        }
+    }
+}
+
+/// MQTT v5 flow: same fixed-interval/on-demand behaviour as the v3 backend,
+/// but an on-demand command carrying `ResponseTopic`/`CorrelationData`
+/// properties gets its result published straight back to that response
+/// topic, echoing the correlation data, instead of only updating the regular
+/// state topics.
+pub struct V5Backend;
+
+impl MqttBackend for V5Backend {
+    type Client = Client;
+    type Connection = Connection;
+    type Request = RequestContext;
+
+    async fn initialize(config: &Config) -> Result<(Self::Client, Self::Connection), ServiceError> {
+        mqtt_shared::initialize_mqtt::<MqttOptions, Client>(config).await
+    }
+
+    fn subscribe_command(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::subscribe_command_topic(client, config)
+    }
+
+    async fn publish_discovery(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::publish_discovery_message(client, config).await
+    }
+
+    fn publish_online(client: &Self::Client, config: &Config) -> Result<(), ServiceError> {
+        mqtt_shared::publish_online(client, config)
+    }
+
+    fn publish_results(client: &Self::Client, config: &Config, result: &SpeedTestResult) {
+        mqtt_shared::publish_results(client, config, result)
+    }
+
+    fn publish_stats(
+        client: &Self::Client,
+        config: &Config,
+        aggregate: &StatsAggregate,
+    ) -> Result<(), ServiceError> {
+        mqtt_shared::publish_stats(client, config, aggregate)
+    }
+
+    fn publish_response(
+        client: &Self::Client,
+        request: &Self::Request,
+        payload: String,
+    ) -> Result<(), ServiceError> {
+        publish_response(client, request, payload)
+    }
+
+    async fn poll(
+        connection: &mut Self::Connection,
+        config: &Config,
+    ) -> Result<PollOutcome<Self::Request>, ServiceError> {
+        poll(connection, config).await
+    }
+}
+
+#[cfg(test)]
+mod request_context_tests {
+    use super::*;
+
+    fn publish_with_properties(properties: Option<PublishProperties>) -> Publish {
+        let mut publish = Publish::new("homeassistant/sensor/speedtest/command", QoS::AtLeastOnce, "");
+        publish.properties = properties;
+        publish
+    }
+
+    #[test]
+    fn no_properties_yields_no_context() {
+        let publish = publish_with_properties(None);
+        assert!(request_context_from_publish(&publish).is_none());
+    }
+
+    #[test]
+    fn properties_missing_response_topic_yields_no_context() {
+        let publish = publish_with_properties(Some(PublishProperties {
+            correlation_data: Some("token".into()),
+            ..Default::default()
+        }));
+        assert!(request_context_from_publish(&publish).is_none());
+    }
+
+    #[test]
+    fn properties_missing_correlation_data_yields_no_context() {
+        let publish = publish_with_properties(Some(PublishProperties {
+            response_topic: Some("homeassistant/sensor/speedtest/response".to_string()),
+            ..Default::default()
+        }));
+        assert!(request_context_from_publish(&publish).is_none());
+    }
+
+    #[test]
+    fn response_topic_and_correlation_data_are_extracted() {
+        let publish = publish_with_properties(Some(PublishProperties {
+            response_topic: Some("homeassistant/sensor/speedtest/response".to_string()),
+            correlation_data: Some("token".into()),
+            ..Default::default()
+        }));
+
+        let ctx = request_context_from_publish(&publish).expect("properties present");
+        assert_eq!(ctx.response_topic, "homeassistant/sensor/speedtest/response");
+        assert_eq!(ctx.correlation_data, b"token");
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::VecDeque, time::Duration};
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::Serialize;
+
+use crate::TestResults;
+
+/// Rolling window of recent results (`STATS_WINDOW` wide) used to compute
+/// trend/stability sensors on top of the latest instantaneous reading.
+pub struct StatsWindow {
+    window: ChronoDuration,
+    samples: VecDeque<(DateTime<Utc>, TestResults)>,
+}
+
+/// Per-metric min/max/mean plus ping jitter (mean absolute difference
+/// between consecutive ping samples) over the current window.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsAggregate {
+    pub download_min: f64,
+    pub download_max: f64,
+    pub download_avg: f64,
+    pub upload_min: f64,
+    pub upload_max: f64,
+    pub upload_avg: f64,
+    pub ping_min: f64,
+    pub ping_max: f64,
+    pub ping_avg: f64,
+    pub ping_jitter: f64,
+}
+
+impl StatsWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: ChronoDuration::from_std(window).unwrap_or(ChronoDuration::hours(24)),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Buffers `result`, evicting any samples older than the configured
+    /// window.
+    pub fn push(&mut self, result: TestResults) {
+        let now = Utc::now();
+        self.samples.push_back((now, result));
+
+        let cutoff = now - self.window;
+        while matches!(self.samples.front(), Some((timestamp, _)) if *timestamp < cutoff) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Recomputes min/max/mean/jitter over the samples currently in the
+    /// window. Returns `None` until the first sample is pushed.
+    pub fn aggregate(&self) -> Option<StatsAggregate> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let downloads: Vec<f64> = self.samples.iter().map(|(_, r)| r.download).collect();
+        let uploads: Vec<f64> = self.samples.iter().map(|(_, r)| r.upload).collect();
+        let pings: Vec<f64> = self.samples.iter().map(|(_, r)| r.ping).collect();
+
+        let ping_jitter = if pings.len() > 1 {
+            let total: f64 = pings.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum();
+            total / (pings.len() - 1) as f64
+        } else {
+            0.0
+        };
+
+        Some(StatsAggregate {
+            download_min: min(&downloads),
+            download_max: max(&downloads),
+            download_avg: mean(&downloads),
+            upload_min: min(&uploads),
+            upload_max: max(&uploads),
+            upload_avg: mean(&uploads),
+            ping_min: min(&pings),
+            ping_max: max(&pings),
+            ping_avg: mean(&pings),
+            ping_jitter,
+        })
+    }
+}
+
+fn min(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::INFINITY, f64::min)
+}
+
+fn max(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(download: f64, upload: f64, ping: f64) -> TestResults {
+        TestResults {
+            download,
+            upload,
+            ping,
+        }
+    }
+
+    #[test]
+    fn aggregate_is_none_before_first_sample() {
+        let window = StatsWindow::new(Duration::from_secs(3600));
+        assert!(window.aggregate().is_none());
+    }
+
+    #[test]
+    fn aggregate_with_single_sample_has_zero_jitter() {
+        let mut window = StatsWindow::new(Duration::from_secs(3600));
+        window.push(sample(100.0, 10.0, 20.0));
+
+        let aggregate = window.aggregate().expect("one sample pushed");
+        assert_eq!(aggregate.download_min, 100.0);
+        assert_eq!(aggregate.download_max, 100.0);
+        assert_eq!(aggregate.download_avg, 100.0);
+        assert_eq!(aggregate.ping_jitter, 0.0);
+    }
+
+    #[test]
+    fn aggregate_over_multiple_samples_computes_min_max_avg_and_jitter() {
+        let mut window = StatsWindow::new(Duration::from_secs(3600));
+        window.push(sample(100.0, 10.0, 10.0));
+        window.push(sample(200.0, 20.0, 15.0));
+        window.push(sample(150.0, 15.0, 5.0));
+
+        let aggregate = window.aggregate().expect("samples pushed");
+        assert_eq!(aggregate.download_min, 100.0);
+        assert_eq!(aggregate.download_max, 200.0);
+        assert_eq!(aggregate.download_avg, 150.0);
+        assert_eq!(aggregate.upload_min, 10.0);
+        assert_eq!(aggregate.upload_max, 20.0);
+        assert_eq!(aggregate.upload_avg, 15.0);
+        // |15-10| + |5-15| = 5 + 10 = 15, over 2 gaps -> 7.5
+        assert_eq!(aggregate.ping_jitter, 7.5);
+    }
+
+    #[test]
+    fn push_evicts_samples_older_than_the_window() {
+        let mut window = StatsWindow::new(Duration::from_secs(60));
+
+        // Backdate a sample well outside the window, then push a fresh one;
+        // the stale sample must not survive the next eviction pass.
+        window
+            .samples
+            .push_back((Utc::now() - ChronoDuration::hours(1), sample(100.0, 10.0, 10.0)));
+        window.push(sample(50.0, 5.0, 5.0));
+
+        assert_eq!(window.samples.len(), 1);
+        let aggregate = window.aggregate().expect("one sample remains");
+        assert_eq!(aggregate.download_min, 50.0);
+        assert_eq!(aggregate.download_max, 50.0);
+    }
+}
@@ -2,19 +2,40 @@ use log::debug;
 use speedtest_rs::speedtest;
 use tokio::task;
 
-use crate::{errors::ServiceError, TestResults};
+use crate::{config::Config, errors::ServiceError, TestResults};
 
-pub async fn perform_all_tests() -> Result<TestResults, ServiceError> {
-    let download_task = task::spawn(perform_download_test());
-    let upload_task = task::spawn(perform_upload_test());
-    let ping_task = task::spawn(perform_ping_test());
+/// Runs download, upload and ping against a single resolved server instead
+/// of each metric picking (and potentially disagreeing on) its own server.
+pub async fn perform_all_tests(config: &Config) -> Result<TestResults, ServiceError> {
+    let server_id = config.speedtest_server_id;
 
-    let (download, upload, ping) = tokio::join!(download_task, upload_task, ping_task);
+    // Resolve configuration and the best server exactly once per cycle. When
+    // `SPEEDTEST_SERVER_ID` is set, latency is only measured against that
+    // pinned server instead of being used to pick one out of the full list.
+    // `SpeedTestLatencyTestResult::server` borrows from `candidates`, so the
+    // owned server and its latency are pulled out before the closure (and
+    // `candidates` with it) goes out of scope.
+    let (speedtest_config, server, ping) = task::spawn_blocking(move || {
+        let speedtest_config = speedtest::get_configuration()?;
+        let server_list = speedtest::get_server_list_with_config(&speedtest_config)?;
 
-    // Flatten and process results using a helper function
-    let download = download.map_err(|_| ServiceError::TaskJoinError)??;
-    let upload = upload.map_err(|_| ServiceError::TaskJoinError)??;
-    let ping = ping.map_err(|_| ServiceError::TaskJoinError)??;
+        let candidates = select_candidates(server_list.servers, server_id)?;
+
+        let best_server = speedtest::get_best_server_based_on_latency(&candidates)?;
+        let ping = best_server.latency.as_secs_f64() * 1000.0; // Convert to milliseconds
+        let server = best_server.server.clone();
+
+        Ok::<_, ServiceError>((speedtest_config, server, ping))
+    })
+    .await??;
+
+    debug!("Resolved speedtest server {:?}", server);
+
+    // `SpeedTestConfig` isn't `Clone`, and download/upload only need mutable
+    // access one at a time, so the config is threaded through the two tests
+    // sequentially instead of being shared across a `tokio::join!`.
+    let (download, speedtest_config) = perform_download_test(speedtest_config, server.clone()).await?;
+    let upload = perform_upload_test(speedtest_config, server).await?;
 
     Ok(TestResults {
         download,
@@ -23,49 +44,98 @@ pub async fn perform_all_tests() -> Result<TestResults, ServiceError> {
     })
 }
 
-async fn perform_download_test() -> Result<f64, ServiceError> {
-    let result = task::spawn_blocking(|| {
-        let mut config = speedtest::get_configuration()?;
-        let servers = speedtest::get_server_list_with_config(&config)?;
-        let best_server = speedtest::get_best_server_based_on_latency(&servers.servers)?;
-        debug!("Performing download test to server {:?}", best_server.server);
-        let download_measurement = speedtest::test_download_with_progress_and_config(
-            best_server.server,
-            || {},
-            &mut config,
-        )?;
-        Ok::<f64, ServiceError>(download_measurement.bps_f64() / 1_000_000.0) // Convert to Mbps
+/// Picks which servers `get_best_server_based_on_latency` should consider:
+/// just the pinned `SPEEDTEST_SERVER_ID` server if one is configured,
+/// otherwise the full list. Split out from [`perform_all_tests`] so this
+/// selection can be unit tested without the network calls that build
+/// `server_list`.
+fn select_candidates(
+    servers: Vec<speedtest::SpeedTestServer>,
+    server_id: Option<u32>,
+) -> Result<Vec<speedtest::SpeedTestServer>, ServiceError> {
+    match server_id {
+        Some(id) => {
+            let server = servers
+                .into_iter()
+                .find(|server| server.id == id)
+                .ok_or(ServiceError::UnknownSpeedtestServer(id))?;
+            Ok(vec![server])
+        }
+        None => Ok(servers),
+    }
+}
+
+#[cfg(test)]
+mod select_candidates_tests {
+    use super::*;
+    use speedtest_rs::distance::EarthLocation;
+
+    fn server(id: u32) -> speedtest::SpeedTestServer {
+        speedtest::SpeedTestServer {
+            country: "Testland".to_string(),
+            host: format!("{id}.example.com:8080"),
+            id,
+            location: EarthLocation::default(),
+            distance: None,
+            name: id.to_string(),
+            sponsor: "Test Sponsor".to_string(),
+            url: format!("http://{id}.example.com/speedtest/upload.php"),
+        }
+    }
+
+    #[test]
+    fn no_id_configured_keeps_the_full_list() {
+        let servers = vec![server(1), server(2), server(3)];
+
+        let candidates = select_candidates(servers.clone(), None).unwrap();
+
+        assert_eq!(candidates.len(), servers.len());
+    }
+
+    #[test]
+    fn known_id_narrows_to_that_single_server() {
+        let servers = vec![server(1), server(2), server(3)];
+
+        let candidates = select_candidates(servers, Some(2)).unwrap();
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, 2);
+    }
+
+    #[test]
+    fn unknown_id_is_rejected() {
+        let servers = vec![server(1), server(2)];
+
+        let err = select_candidates(servers, Some(99)).unwrap_err();
+
+        assert!(matches!(err, ServiceError::UnknownSpeedtestServer(id) if id == 99));
+    }
+}
+
+async fn perform_download_test(
+    mut config: speedtest::SpeedTestConfig,
+    server: speedtest::SpeedTestServer,
+) -> Result<(f64, speedtest::SpeedTestConfig), ServiceError> {
+    let result = task::spawn_blocking(move || {
+        debug!("Performing download test to server {:?}", server);
+        let download_measurement =
+            speedtest::test_download_with_progress_and_config(&server, || {}, &mut config)?;
+        Ok::<_, ServiceError>((download_measurement.bps_f64() / 1_000_000.0, config)) // Convert to Mbps
     })
     .await??;
     Ok(result)
 }
 
-async fn perform_upload_test() -> Result<f64, ServiceError> {
-    let result = task::spawn_blocking(|| {
-        let config = speedtest::get_configuration()?;
-        let servers = speedtest::get_server_list_with_config(&config)?;
-        let best_server = speedtest::get_best_server_based_on_latency(&servers.servers)?;
-        debug!("Performing upload test to server {:?}", best_server.server);
+async fn perform_upload_test(
+    config: speedtest::SpeedTestConfig,
+    server: speedtest::SpeedTestServer,
+) -> Result<f64, ServiceError> {
+    let result = task::spawn_blocking(move || {
+        debug!("Performing upload test to server {:?}", server);
         let upload_measurement =
-            speedtest::test_upload_with_progress_and_config(best_server.server, || {}, &config)?;
+            speedtest::test_upload_with_progress_and_config(&server, || {}, &config)?;
         Ok::<f64, ServiceError>(upload_measurement.bps_f64() / 1_000_000.0) // Convert to Mbps
     })
     .await??;
     Ok(result)
 }
-
-async fn perform_ping_test() -> Result<f64, ServiceError> {
-    let result = task::spawn_blocking(|| {
-        let config = speedtest::get_configuration().map_err(ServiceError::from)?;
-        let servers =
-            speedtest::get_server_list_with_config(&config).map_err(ServiceError::from)?;
-        let best_server = speedtest::get_best_server_based_on_latency(&servers.servers)
-            .map_err(ServiceError::from)?;
-        debug!("Performing ping test to server {:?}", best_server.server);
-
-        Ok::<f64, ServiceError>(best_server.latency.as_secs_f64())
-    })
-    .await??;
-
-    Ok(result * 1000.0) // Convert to milliseconds
-}
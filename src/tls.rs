@@ -0,0 +1,73 @@
+use crate::{config::Config, errors::ServiceError};
+use log::warn;
+use rumqttc::{TlsConfiguration, Transport};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ClientConfig, RootCertStore,
+};
+use std::{fs, io::BufReader, sync::Arc};
+
+/// Builds the rustls-backed transport for `MQTT_TLS=true`, shared by the v3
+/// and v5 client setup since both accept the same `rumqttc::Transport`.
+///
+/// Loads `MQTT_CA_CERT` for server verification, falling back to the
+/// platform trust store when it isn't set, and additionally configures
+/// mutual TLS when `MQTT_CLIENT_CERT`/`MQTT_CLIENT_KEY` are both present.
+pub fn build_transport(config: &Config) -> Result<Transport, ServiceError> {
+    let mut root_store = RootCertStore::empty();
+    if let Some(ca_path) = &config.mqtt_ca_cert {
+        for cert in load_certs(ca_path)? {
+            root_store
+                .add(cert)
+                .map_err(|_| ServiceError::TlsCertParse)?;
+        }
+    } else {
+        // `load_native_certs` returns a `CertificateResult` rather than a
+        // `Result`: loading is best-effort per-certificate, so a handful of
+        // unreadable system certs shouldn't fail the whole connection as
+        // long as some usable ones came back.
+        let native_certs = rustls_native_certs::load_native_certs();
+        for err in &native_certs.errors {
+            warn!("Failed to load a native root certificate: {:?}", err);
+        }
+        for cert in native_certs.certs {
+            root_store
+                .add(cert)
+                .map_err(|_| ServiceError::TlsCertParse)?;
+        }
+    }
+
+    let tls_config_builder = ClientConfig::builder().with_root_certificates(root_store);
+
+    let tls_config = match (&config.mqtt_client_cert, &config.mqtt_client_key) {
+        (Some(cert_path), Some(key_path)) => tls_config_builder
+            .with_client_auth_cert(load_certs(cert_path)?, load_private_key(key_path)?)
+            .map_err(|_| ServiceError::TlsCertParse)?,
+        (Some(_), None) | (None, Some(_)) => {
+            warn!(
+                "MQTT_CLIENT_CERT and MQTT_CLIENT_KEY must both be set for mutual TLS; only one \
+                 was provided, so connecting without client auth"
+            );
+            tls_config_builder.with_no_client_auth()
+        }
+        (None, None) => tls_config_builder.with_no_client_auth(),
+    };
+
+    Ok(Transport::Tls(TlsConfiguration::Rustls(Arc::new(
+        tls_config,
+    ))))
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, ServiceError> {
+    let pem = fs::read(path)?;
+    rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ServiceError::TlsCertParse)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, ServiceError> {
+    let pem = fs::read(path)?;
+    rustls_pemfile::private_key(&mut BufReader::new(pem.as_slice()))
+        .map_err(|_| ServiceError::TlsCertParse)?
+        .ok_or(ServiceError::TlsCertParse)
+}